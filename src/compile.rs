@@ -0,0 +1,185 @@
+use flate2::read::GzDecoder;
+use tar::Archive;
+use tempdir::TempDir;
+use toml;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use errors::Error;
+use http_client::HttpClient;
+use registry::RevDep;
+use load_string;
+
+#[derive(Clone)]
+pub enum CrateOverride {
+    Default,
+    Source(PathBuf)
+}
+
+#[derive(Debug, Clone)]
+pub struct CompileResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub success: bool
+}
+
+impl CompileResult {
+    pub fn failed(&self) -> bool {
+        !self.success
+    }
+
+    fn error(msg: String) -> CompileResult {
+        CompileResult {
+            stdout: String::new(),
+            stderr: msg,
+            success: false
+        }
+    }
+}
+
+/// Compiles `rev_dep` against `krate`, either the published version of the
+/// crate under test (`CrateOverride::Default`) or a local checkout of it
+/// (`CrateOverride::Source`). Downloads and unpacks the rev dep's source
+/// from crates.io into a fresh temp dir, optionally patches its manifest to
+/// point at the local source, then shells out to `cargo build`.
+pub fn compile_with_custom_dep(client: &HttpClient, crate_name: &str, rev_dep: &RevDep, krate: &CrateOverride) -> CompileResult {
+    match try_compile(client, crate_name, rev_dep, krate) {
+        Ok(result) => result,
+        Err(e) => CompileResult::error(format!("{:?}", e))
+    }
+}
+
+fn try_compile(client: &HttpClient, crate_name: &str, rev_dep: &RevDep, krate: &CrateOverride) -> Result<CompileResult, Error> {
+    let work_dir = try!(TempDir::new("cargo-crusader"));
+    let crate_dir = try!(download_and_unpack(client, rev_dep, work_dir.path()));
+
+    if let CrateOverride::Source(ref manifest_path) = *krate {
+        try!(patch_manifest(&crate_dir, crate_name, manifest_path));
+    }
+
+    Ok(run_cargo_build(&crate_dir))
+}
+
+fn download_url(rev_dep: &RevDep) -> String {
+    format!("https://crates.io/api/v1/crates/{}/{}/download", rev_dep.name, rev_dep.vers)
+}
+
+fn download_and_unpack(client: &HttpClient, rev_dep: &RevDep, into: &Path) -> Result<PathBuf, Error> {
+    let ref url = download_url(rev_dep);
+    info!("downloading {} {} from {}", rev_dep.name, rev_dep.vers, url);
+
+    let body = try!(client.get(url));
+
+    let decoder = try!(GzDecoder::new(&body[..]));
+    let mut archive = Archive::new(decoder);
+    try!(archive.unpack(into));
+
+    Ok(into.join(format!("{}-{}", rev_dep.name, rev_dep.vers)))
+}
+
+/// Points `crate_name` at `override_dir` in the dependent's `[patch.crates-io]`
+/// table. Parses the manifest and merges into any `patch`/`crates-io` tables
+/// that already exist rather than appending a second copy, since a duplicate
+/// table is invalid TOML and would otherwise surface as a spurious `Fail`
+/// instead of the tooling limitation it actually is.
+fn patch_manifest(crate_dir: &Path, crate_name: &str, override_manifest: &Path) -> Result<(), Error> {
+    let manifest_path = crate_dir.join("Cargo.toml");
+    let override_dir = override_manifest.parent().unwrap_or(Path::new("."));
+
+    let manifest = try!(load_string(&manifest_path));
+    let mut parser = toml::Parser::new(&manifest);
+    let mut root = match parser.parse() {
+        Some(root) => root,
+        None => return Err(Error::TomlError(parser.errors))
+    };
+
+    let mut patch = match root.remove("patch") {
+        Some(toml::Value::Table(t)) => t,
+        _ => toml::Table::new()
+    };
+    let mut crates_io = match patch.remove("crates-io") {
+        Some(toml::Value::Table(t)) => t,
+        _ => toml::Table::new()
+    };
+
+    let mut dep = toml::Table::new();
+    dep.insert("path".to_string(), toml::Value::String(format!("{}", override_dir.display())));
+    crates_io.insert(crate_name.to_string(), toml::Value::Table(dep));
+
+    patch.insert("crates-io".to_string(), toml::Value::Table(crates_io));
+    root.insert("patch".to_string(), toml::Value::Table(patch));
+
+    let patched = toml::Value::Table(root).to_string();
+
+    let mut file = try!(fs::File::create(&manifest_path));
+    try!(file.write_all(patched.as_bytes()));
+
+    Ok(())
+}
+
+fn run_cargo_build(crate_dir: &Path) -> CompileResult {
+    match Command::new("cargo").arg("build").current_dir(crate_dir).output() {
+        Ok(output) => CompileResult {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            success: output.status.success()
+        },
+        Err(e) => CompileResult::error(format!("failed to run cargo: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::patch_manifest;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn patch_manifest_merges_into_existing_patch_table() {
+        let crate_dir = TempDir::new("cargo-crusader-test").unwrap();
+        let override_dir = TempDir::new("cargo-crusader-test-override").unwrap();
+
+        let manifest_path = crate_dir.path().join("Cargo.toml");
+        fs::write(&manifest_path, r#"
+[package]
+name = "somecrate"
+version = "0.1.0"
+
+[patch.crates-io]
+other-crate = { git = "https://example.com/other-crate" }
+"#).unwrap();
+
+        let override_manifest = override_dir.path().join("Cargo.toml");
+        fs::write(&override_manifest, "[package]\nname = \"target-crate\"\n").unwrap();
+
+        patch_manifest(crate_dir.path(), "target-crate", &override_manifest).unwrap();
+
+        let patched = fs::read_to_string(&manifest_path).unwrap();
+        assert_eq!(patched.matches("[patch.crates-io]").count(), 1,
+                    "must not duplicate the patch.crates-io table: {}", patched);
+        assert!(patched.contains("other-crate"),
+                "must keep the pre-existing patch entry: {}", patched);
+        assert!(patched.contains("target-crate"),
+                "must add the new patch entry: {}", patched);
+    }
+
+    #[test]
+    fn patch_manifest_creates_patch_table_when_absent() {
+        let crate_dir = TempDir::new("cargo-crusader-test").unwrap();
+        let override_dir = TempDir::new("cargo-crusader-test-override").unwrap();
+
+        let manifest_path = crate_dir.path().join("Cargo.toml");
+        fs::write(&manifest_path, "[package]\nname = \"somecrate\"\nversion = \"0.1.0\"\n").unwrap();
+
+        let override_manifest = override_dir.path().join("Cargo.toml");
+        fs::write(&override_manifest, "[package]\nname = \"target-crate\"\n").unwrap();
+
+        patch_manifest(crate_dir.path(), "target-crate", &override_manifest).unwrap();
+
+        let patched = fs::read_to_string(&manifest_path).unwrap();
+        assert!(patched.contains("[patch.crates-io]"));
+        assert!(patched.contains("target-crate"));
+    }
+}
@@ -0,0 +1,157 @@
+use csv;
+use curl;
+use curl::http::Response as CurlHttpResponse;
+use rustc_serialize::json;
+use semver;
+use toml;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::Utf8Error;
+use std::sync::mpsc::RecvError;
+
+use TestResult;
+
+#[derive(Debug)]
+pub enum Error {
+    BadArgs,
+    ManifestName(PathBuf),
+    NoVersions,
+    TestFailure(Vec<TestResult>),
+    SemverError(semver::ParseError),
+    TomlError(Vec<toml::ParserError>),
+    IoError(io::Error),
+    CurlError(curl::ErrCode),
+    HttpError(CurlHttpResponseWrapper),
+    Utf8Error(Utf8Error),
+    JsonDecode(json::DecoderError),
+    JsonEncode(json::EncoderError),
+    RecvError(RecvError),
+    CsvError(csv::Error),
+    CsvColumn(String)
+}
+
+impl From<semver::ParseError> for Error {
+    fn from(e: semver::ParseError) -> Error {
+        Error::SemverError(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+impl From<curl::ErrCode> for Error {
+    fn from(e: curl::ErrCode) -> Error {
+        Error::CurlError(e)
+    }
+}
+
+impl From<Utf8Error> for Error {
+    fn from(e: Utf8Error) -> Error {
+        Error::Utf8Error(e)
+    }
+}
+
+impl From<json::DecoderError> for Error {
+    fn from(e: json::DecoderError) -> Error {
+        Error::JsonDecode(e)
+    }
+}
+
+impl From<RecvError> for Error {
+    fn from(e: RecvError) -> Error {
+        Error::RecvError(e)
+    }
+}
+
+impl From<json::EncoderError> for Error {
+    fn from(e: json::EncoderError) -> Error {
+        Error::JsonEncode(e)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Error {
+        Error::CsvError(e)
+    }
+}
+
+/// Coarse classification of an `Error`, independent of which specific
+/// variant it is. Lets reporting group failures (e.g. "network" vs.
+/// "io") without matching on every variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    Args,
+    Manifest,
+    Network,
+    Toml,
+    Io,
+    Semver,
+    Decode,
+    Recv,
+    TestFailure
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            Error::BadArgs => ErrorKind::Args,
+            Error::ManifestName(..) => ErrorKind::Manifest,
+            Error::NoVersions => ErrorKind::Network,
+            Error::TestFailure(..) => ErrorKind::TestFailure,
+            Error::SemverError(..) => ErrorKind::Semver,
+            Error::TomlError(..) => ErrorKind::Toml,
+            Error::IoError(..) => ErrorKind::Io,
+            Error::CurlError(..) => ErrorKind::Network,
+            Error::HttpError(..) => ErrorKind::Network,
+            Error::Utf8Error(..) => ErrorKind::Decode,
+            Error::JsonDecode(..) => ErrorKind::Decode,
+            Error::JsonEncode(..) => ErrorKind::Decode,
+            Error::RecvError(..) => ErrorKind::Recv,
+            Error::CsvError(..) => ErrorKind::Decode,
+            Error::CsvColumn(..) => ErrorKind::Decode
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BadArgs => write!(f, "invalid arguments"),
+            Error::ManifestName(ref p) => {
+                write!(f, "could not read a crate name from manifest {:?}", p)
+            }
+            Error::NoVersions => write!(f, "crate has no published, non-yanked versions"),
+            Error::TestFailure(ref results) => {
+                write!(f, "{} reverse dependency(s) regressed",
+                       results.iter().filter(|r| r.failed()).count())
+            }
+            Error::SemverError(ref e) => write!(f, "semver error: {}", e),
+            Error::TomlError(ref errs) => write!(f, "toml parse error: {:?}", errs),
+            Error::IoError(ref e) => write!(f, "io error: {}", e),
+            Error::CurlError(ref e) => write!(f, "curl error: {:?}", e),
+            Error::HttpError(ref r) => write!(f, "http error: {:?}", r),
+            Error::Utf8Error(ref e) => write!(f, "utf8 error: {}", e),
+            Error::JsonDecode(ref e) => write!(f, "json decode error: {}", e),
+            Error::JsonEncode(ref e) => write!(f, "json encode error: {}", e),
+            Error::RecvError(ref e) => write!(f, "recv error: {}", e),
+            Error::CsvError(ref e) => write!(f, "csv error: {}", e),
+            Error::CsvColumn(ref msg) => write!(f, "csv error: {}", msg)
+        }
+    }
+}
+
+pub struct CurlHttpResponseWrapper(pub CurlHttpResponse);
+
+impl fmt::Debug for CurlHttpResponseWrapper {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let CurlHttpResponseWrapper(ref resp) = *self;
+        let tup = (resp.get_code(), resp.get_headers(), resp.get_body());
+        try!(fmt.write_str(&format!("{:?}", tup)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use curl::http;
+use std::cell::RefCell;
+use std::cmp;
+use std::sync::{Mutex, Condvar};
+use std::thread;
+use std::time::Duration;
+
+use errors::{Error, CurlHttpResponseWrapper};
+
+const DEFAULT_USER_AGENT: &'static str = "cargo-crusader (https://github.com/brson/cargo-crusader)";
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const INITIAL_BACKOFF_MS: u64 = 250;
+const MAX_BACKOFF_MS: u64 = 8000;
+
+thread_local! {
+    // A curl easy handle is reasonably expensive to set up, and isn't
+    // `Send`, so each worker thread gets its own and reuses it across
+    // every request it makes rather than building a fresh one per call.
+    static HANDLE: RefCell<http::Handle> = RefCell::new(http::handle());
+}
+
+/// A simple counting semaphore used to cap how many requests are in
+/// flight against crates.io at once, regardless of how many worker
+/// threads are fanned out.
+struct Semaphore {
+    count: Mutex<usize>,
+    cond: Condvar
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            count: Mutex::new(permits),
+            cond: Condvar::new()
+        }
+    }
+
+    fn acquire(&self) {
+        let mut count = self.count.lock().unwrap();
+        while *count == 0 {
+            count = self.cond.wait(count).unwrap();
+        }
+        *count -= 1;
+    }
+
+    fn release(&self) {
+        let mut count = self.count.lock().unwrap();
+        *count += 1;
+        self.cond.notify_one();
+    }
+}
+
+struct Permit<'a>(&'a Semaphore);
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// A well-behaved crates.io client: reuses a per-thread curl handle,
+/// identifies itself with a `User-Agent`, retries transient failures and
+/// HTTP 429/503 with exponential backoff, and limits how many requests
+/// can be outstanding across the whole process at once.
+pub struct HttpClient {
+    user_agent: String,
+    max_retries: u32,
+    semaphore: Semaphore
+}
+
+impl HttpClient {
+    pub fn new(max_concurrency: usize) -> HttpClient {
+        HttpClient {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            max_retries: DEFAULT_MAX_RETRIES,
+            semaphore: Semaphore::new(max_concurrency)
+        }
+    }
+
+    pub fn get_string(&self, url: &str) -> Result<String, Error> {
+        let body = try!(self.get(url));
+        let s = try!(String::from_utf8(body).map_err(|e| e.utf8_error()));
+        Ok(s)
+    }
+
+    pub fn get(&self, url: &str) -> Result<Vec<u8>, Error> {
+        self.semaphore.acquire();
+        let _permit = Permit(&self.semaphore);
+
+        let mut attempt = 0;
+        let mut backoff_ms = INITIAL_BACKOFF_MS;
+
+        loop {
+            attempt += 1;
+
+            let result = HANDLE.with(|handle| {
+                let mut handle = handle.borrow_mut();
+                handle.get(url)
+                    .header("User-Agent", &self.user_agent)
+                    .follow_redirects(true)
+                    .exec()
+            });
+
+            match result {
+                Ok(resp) => {
+                    let code = resp.get_code();
+                    if let Some(wait_ms) = retry_after_status(code, attempt, self.max_retries, backoff_ms) {
+                        warn!("got {} from {}, retrying in {}ms (attempt {}/{})",
+                              code, url, wait_ms, attempt, self.max_retries);
+                        thread::sleep(Duration::from_millis(wait_ms));
+                        backoff_ms = next_backoff_ms(backoff_ms);
+                        continue;
+                    }
+                    if code != 200 {
+                        return Err(Error::HttpError(CurlHttpResponseWrapper(resp)));
+                    }
+                    return Ok(resp.get_body().to_vec());
+                }
+                Err(e) => {
+                    match retry_after_error(attempt, self.max_retries, backoff_ms) {
+                        Some(wait_ms) => {
+                            warn!("transient curl error {:?} for {}, retrying in {}ms (attempt {}/{})",
+                                  e, url, wait_ms, attempt, self.max_retries);
+                            thread::sleep(Duration::from_millis(wait_ms));
+                            backoff_ms = next_backoff_ms(backoff_ms);
+                        }
+                        None => return Err(Error::from(e))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether an HTTP response with `code` should be retried, and how long to
+/// wait first if so. `attempt` is 1 for the first try. Factored out of
+/// `get` so the retry/backoff policy can be tested without a live network
+/// call.
+fn retry_after_status(code: u32, attempt: u32, max_retries: u32, backoff_ms: u64) -> Option<u64> {
+    if (code == 429 || code == 503) && attempt <= max_retries {
+        Some(backoff_ms)
+    } else {
+        None
+    }
+}
+
+/// Whether a transient curl error should be retried, and how long to wait
+/// first if so. `attempt` is 1 for the first try.
+fn retry_after_error(attempt: u32, max_retries: u32, backoff_ms: u64) -> Option<u64> {
+    if attempt <= max_retries {
+        Some(backoff_ms)
+    } else {
+        None
+    }
+}
+
+fn next_backoff_ms(backoff_ms: u64) -> u64 {
+    cmp::min(backoff_ms * 2, MAX_BACKOFF_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_backoff_ms, retry_after_error, retry_after_status, MAX_BACKOFF_MS, DEFAULT_MAX_RETRIES};
+
+    #[test]
+    fn retries_429_and_503_within_the_retry_cap() {
+        assert_eq!(retry_after_status(429, 1, DEFAULT_MAX_RETRIES, 250), Some(250));
+        assert_eq!(retry_after_status(503, DEFAULT_MAX_RETRIES, DEFAULT_MAX_RETRIES, 250), Some(250));
+    }
+
+    #[test]
+    fn gives_up_on_429_503_once_the_retry_cap_is_exceeded() {
+        assert_eq!(retry_after_status(429, DEFAULT_MAX_RETRIES + 1, DEFAULT_MAX_RETRIES, 250), None);
+    }
+
+    #[test]
+    fn does_not_retry_other_status_codes() {
+        assert_eq!(retry_after_status(200, 1, DEFAULT_MAX_RETRIES, 250), None);
+        assert_eq!(retry_after_status(404, 1, DEFAULT_MAX_RETRIES, 250), None);
+        assert_eq!(retry_after_status(500, 1, DEFAULT_MAX_RETRIES, 250), None);
+    }
+
+    #[test]
+    fn retries_transient_errors_within_the_retry_cap() {
+        assert_eq!(retry_after_error(1, DEFAULT_MAX_RETRIES, 250), Some(250));
+        assert_eq!(retry_after_error(DEFAULT_MAX_RETRIES, DEFAULT_MAX_RETRIES, 250), Some(250));
+    }
+
+    #[test]
+    fn gives_up_on_transient_errors_once_the_retry_cap_is_exceeded() {
+        assert_eq!(retry_after_error(DEFAULT_MAX_RETRIES + 1, DEFAULT_MAX_RETRIES, 250), None);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max() {
+        let mut backoff = 250;
+        for _ in 0..10 {
+            backoff = next_backoff_ms(backoff);
+            assert!(backoff <= MAX_BACKOFF_MS);
+        }
+        assert_eq!(backoff, MAX_BACKOFF_MS);
+    }
+}
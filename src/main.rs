@@ -7,21 +7,40 @@ extern crate semver;
 extern crate toml;
 extern crate threadpool;
 extern crate num_cpus;
+extern crate tar;
+extern crate flate2;
+extern crate tempdir;
+extern crate csv;
+extern crate fnv;
+
+mod errors;
+mod registry;
+mod compile;
+mod report;
+mod offline;
+mod http_client;
 
-use curl::{http, ErrCode};
-use curl::http::Response as CurlHttpResponse;
-use rustc_serialize::json;
 use semver::Version;
-use std::convert::From;
 use std::env;
-use std::fmt;
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::Read;
 use std::path::{PathBuf, Path};
-use std::str::{self, Utf8Error};
-use std::sync::mpsc::{self, Sender, Receiver, RecvError};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender, Receiver};
 use threadpool::ThreadPool;
 
+use errors::Error;
+use registry::{get_rev_deps, get_published_version, filter_rev_deps, resolve_rev_dep_version, RevDep, RevDepName, RevDepRef};
+use compile::{compile_with_custom_dep, CrateOverride, CompileResult};
+use report::report_results;
+use offline::RevDepMap;
+use http_client::HttpClient;
+
+// crates.io asks well-behaved clients to keep concurrent requests modest;
+// this is independent of the thread pool size, which is driven by how many
+// rev deps we can usefully *compile* at once.
+const MAX_HTTP_CONCURRENCY: usize = 4;
+
 fn main() {
     env_logger::init().unwrap();
     report_results(run());
@@ -29,13 +48,30 @@ fn main() {
 
 fn run() -> Result<Vec<TestResult>, Error> {
     let config = try!(get_config());
+    let http_client = Arc::new(HttpClient::new(MAX_HTTP_CONCURRENCY));
+
+    let offline_map = match config.offline_dump {
+        Some(ref path) => Some(Arc::new(try!(offline::load_or_fetch(&http_client, path)))),
+        None => None
+    };
+
+    let rev_deps = match offline_map {
+        Some(ref map) => map.rev_deps_of(&config.crate_name),
+        None => try!(get_rev_deps(&http_client, &config.crate_name))
+    };
+    let base_version = match offline_map {
+        Some(ref map) => try!(map.latest_version(&config.crate_name).ok_or(Error::NoVersions)),
+        None => try!(get_published_version(&http_client, &config.crate_name))
+    };
+    let rev_deps = filter_rev_deps(rev_deps, &base_version);
 
-    let rev_deps = try!(get_rev_deps(&config.crate_name));
     let crates = try!(acquire_crates(&config));
     let mut results = Vec::new();
     let ref mut pool = ThreadPool::new(num_cpus::get());
     for rev_dep in rev_deps {
-        let result = run_test(pool, crates.base.clone(), crates.next.clone(), rev_dep);
+        let result = run_test(pool, http_client.clone(), config.crate_name.clone(),
+                               crates.base.clone(), crates.next.clone(),
+                               rev_dep, offline_map.clone());
         results.push(result);
     }
 
@@ -54,7 +90,8 @@ struct Config {
     manifest_path: PathBuf,
     crate_name: String,
     base_origin: Origin,
-    next_origin: Origin
+    next_origin: Origin,
+    offline_dump: Option<PathBuf>
 }
 
 enum Origin {
@@ -67,15 +104,27 @@ type VersionNumber = String;
 fn get_config() -> Result<Config, Error> {
     let manifest = env::var("CRUSADER_MANIFEST");
     let manifest = manifest.unwrap_or_else(|_| "./Cargo.toml".to_string());
-    let manifest = PathBuf::from(manifest);
+    // Canonicalize now rather than leaving it relative: `patch_manifest`
+    // writes this manifest's directory into the rev dep's manifest as a
+    // `path` patch entry,
+    // which Cargo resolves relative to the manifest declaring it, not to
+    // our cwd - a relative "./Cargo.toml" would silently patch the crate
+    // under test against the wrong directory.
+    let manifest = try!(PathBuf::from(manifest).canonicalize());
     info!("Using manifest {:?}", manifest);
 
     let source_name = try!(get_crate_name(&manifest));
+    let offline_dump = env::var("CRUSADER_OFFLINE_DUMP").ok().map(PathBuf::from);
+    if let Some(ref path) = offline_dump {
+        info!("Using offline crates.io dump at {:?}", path);
+    }
+
     Ok(Config {
         manifest_path: manifest.clone(),
         crate_name: source_name,
         base_origin: Origin::Published,
-        next_origin: Origin::Source(manifest)
+        next_origin: Origin::Source(manifest),
+        offline_dump: offline_dump
     })
 }
 
@@ -113,71 +162,11 @@ fn load_string(path: &Path) -> Result<String, Error> {
     Ok(s)
 }
 
-type RevDepName = String;
-
-fn crate_url(krate: &str, call: Option<&str>) -> String {
-    let url = format!("https://crates.io/api/v1/crates/{}", krate);
-    match call {
-        Some(c) => format!("{}/{}", url, c),
-        None => url
-    }
-}
-
-fn get_rev_deps(crate_name: &str) -> Result<Vec<RevDepName>, Error> {
-    info!("Getting reverse deps for {}", crate_name);
-    let ref url = crate_url(crate_name, Some("reverse_dependencies"));
-    let ref body = try!(http_get_to_string(url));
-    let rev_deps = try!(parse_rev_deps(body));
-
-    Ok(rev_deps)
-}
-
-fn http_get_to_string(url: &str) -> Result<String, Error> {
-    let resp = try!(http::handle().get(url).exec());
-
-    if resp.get_code() != 200 {
-        return Err(Error::HttpError(CurlHttpResponseWrapper(resp)));
-    }
-
-    let body = try!(str::from_utf8(resp.get_body()));
-
-    Ok(String::from(body))
-}
-
-fn parse_rev_deps(s: &str) -> Result<Vec<RevDepName>, Error> {
-    #[derive(RustcEncodable, RustcDecodable)]
-    struct Response {
-        dependencies: Vec<Dep>,
-    }
-
-    #[derive(RustcEncodable, RustcDecodable)]
-    struct Dep {
-        crate_id: String
-    }
-
-    let decoded: Response = try!(json::decode(&s));
-
-    fn depconv(d: Dep) -> RevDepName { d.crate_id }
-
-    let revdeps = decoded.dependencies.into_iter()
-        .map(depconv).collect();
-
-    info!("revdeps: {:?}", revdeps);
-
-    Ok(revdeps)
-}
-
 struct Crates {
     base: CrateOverride,
     next: CrateOverride
 }
 
-#[derive(Clone)]
-enum CrateOverride {
-    Default,
-    Source(PathBuf)
-}
-
 fn acquire_crates(config: &Config) -> Result<Crates, Error> {
     let base = acquire_crate(&config.base_origin);
     let next = acquire_crate(&config.next_origin);
@@ -191,12 +180,6 @@ fn acquire_crate(origin: &Origin) -> CrateOverride {
     }
 }
 
-#[derive(Debug, Clone)]
-struct RevDep {
-    name: RevDepName,
-    vers: Version
-}
-
 #[derive(Debug)]
 struct TestResult {
     rev_dep: RevDep,
@@ -268,11 +251,11 @@ impl TestResultFuture {
     }
 }
 
-fn new_result_future(rev_dep: RevDepName) -> (Sender<TestResult>, TestResultFuture) {
+fn new_result_future(rev_dep: &RevDepRef) -> (Sender<TestResult>, TestResultFuture) {
     let (tx, rx) = mpsc::channel();
 
     let fut = TestResultFuture {
-        rev_dep: rev_dep,
+        rev_dep: rev_dep.name.clone(),
         rx: rx
     };
 
@@ -280,35 +263,52 @@ fn new_result_future(rev_dep: RevDepName) -> (Sender<TestResult>, TestResultFutu
 }
 
 fn run_test(pool: &mut ThreadPool,
+            http_client: Arc<HttpClient>,
+            crate_name: String,
             base_crate: CrateOverride,
             next_crate: CrateOverride,
-            rev_dep: RevDepName) -> TestResultFuture {
-    let (result_tx, result_future) = new_result_future(rev_dep.clone());
+            rev_dep: RevDepRef,
+            offline_map: Option<Arc<RevDepMap>>) -> TestResultFuture {
+    let (result_tx, result_future) = new_result_future(&rev_dep);
     pool.execute(move || {
-        let res = run_test_local(&base_crate, &next_crate, rev_dep);
+        let res = run_test_local(&http_client, &crate_name, &base_crate, &next_crate, rev_dep, &offline_map);
         result_tx.send(res);
     });
 
     return result_future;
 }
 
-fn run_test_local(base_crate: &CrateOverride, next_crate: &CrateOverride, rev_dep: RevDepName) -> TestResult {
-    let rev_dep = match resolve_rev_dep_version(rev_dep.clone()) {
+fn resolve_rev_dep(http_client: &HttpClient, name: RevDepName,
+                    offline_map: &Option<Arc<RevDepMap>>) -> Result<RevDep, Error> {
+    match *offline_map {
+        Some(ref map) => {
+            match map.latest_version(&name) {
+                Some(vers) => Ok(RevDep { name: name, vers: vers }),
+                None => Err(Error::NoVersions)
+            }
+        }
+        None => resolve_rev_dep_version(http_client, name)
+    }
+}
+
+fn run_test_local(http_client: &HttpClient, crate_name: &str, base_crate: &CrateOverride, next_crate: &CrateOverride,
+                   rev_dep: RevDepRef, offline_map: &Option<Arc<RevDepMap>>) -> TestResult {
+    let rev_dep = match resolve_rev_dep(http_client, rev_dep.name.clone(), offline_map) {
         Ok(r) => r,
         Err(e) => {
             let rev_dep = RevDep {
-                name: rev_dep,
+                name: rev_dep.name,
                 vers: Version::parse("0.0.0").unwrap()
             };
             return TestResult::error(rev_dep, e);
         }
     };
-    let base_result = compile_with_custom_dep(&rev_dep, base_crate);
+    let base_result = compile_with_custom_dep(http_client, crate_name, &rev_dep, base_crate);
 
     if base_result.failed() {
         return TestResult::broken(rev_dep, base_result);
     }
-    let next_result = compile_with_custom_dep(&rev_dep, next_crate);
+    let next_result = compile_with_custom_dep(http_client, crate_name, &rev_dep, next_crate);
 
     if next_result.failed() {
         TestResult::fail(rev_dep, base_result, next_result)
@@ -316,112 +316,3 @@ fn run_test_local(base_crate: &CrateOverride, next_crate: &CrateOverride, rev_de
         TestResult::pass(rev_dep, base_result, next_result)
     }
 }
-
-fn resolve_rev_dep_version(name: RevDepName) -> Result<RevDep, Error> {
-    info!("resolving current version for {}", name);
-    let ref url = crate_url(&name, None);
-    let ref body = try!(http_get_to_string(url));
-    let krate = try!(parse_crate(body));
-    println!("{:?}", krate);
-    unimplemented!()
-}
-
-#[derive(RustcEncodable, RustcDecodable, Debug)]
-struct RegistryCrate {
-    versions: Vec<RegistryVersion>
-}
-
-#[derive(RustcEncodable, RustcDecodable, Debug)]
-struct RegistryVersion {
-    num: String
-}
-
-fn parse_crate(s: &str) -> Result<RegistryCrate, Error> {
-    Ok(try!(json::decode(&s)))
-}
-
-#[derive(Debug, Clone)]
-struct CompileResult {
-    stdout: String,
-    stderr: String,
-    success: bool
-}
-
-impl CompileResult {
-    fn failed(&self) -> bool { unimplemented!() }
-}
-
-fn compile_with_custom_dep(rev_dep: &RevDep, krate: &CrateOverride) -> CompileResult {
-    //let temp_dir = get_temp_dir();
-    //let crate_handle = get_crate_handle(rev_dep);
-
-    
-    unimplemented!()
-}
-
-fn report_results(res: Result<Vec<TestResult>, Error>) {
-    println!("results: {:?}", res);
-}
-
-#[derive(Debug)]
-enum Error {
-    BadArgs,
-    ManifestName(PathBuf),
-    TestFailure(Vec<TestResult>),
-    SemverError(semver::ParseError),
-    TomlError(Vec<toml::ParserError>),
-    IoError(io::Error),
-    CurlError(curl::ErrCode),
-    HttpError(CurlHttpResponseWrapper),
-    Utf8Error(Utf8Error),
-    JsonDecode(json::DecoderError),
-    RecvError(RecvError)
-}
-
-impl From<semver::ParseError> for Error {
-    fn from(e: semver::ParseError) -> Error {
-        Error::SemverError(e)
-    }
-}
-
-impl From<io::Error> for Error {
-    fn from(e: io::Error) -> Error {
-        Error::IoError(e)
-    }
-}
-
-impl From<curl::ErrCode> for Error {
-    fn from(e: curl::ErrCode) -> Error {
-        Error::CurlError(e)
-    }
-}
-
-impl From<Utf8Error> for Error {
-    fn from(e: Utf8Error) -> Error {
-        Error::Utf8Error(e)
-    }
-}
-
-impl From<json::DecoderError> for Error {
-    fn from(e: json::DecoderError) -> Error {
-        Error::JsonDecode(e)
-    }
-}
-
-impl From<RecvError> for Error {
-    fn from(e: RecvError) -> Error {
-        Error::RecvError(e)
-    }
-}
-
-struct CurlHttpResponseWrapper(CurlHttpResponse);
-
-impl fmt::Debug for CurlHttpResponseWrapper {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let CurlHttpResponseWrapper(ref resp) = *self;
-        let tup = (resp.get_code(), resp.get_headers(), resp.get_body());
-        try!(fmt.write_str(&format!("{:?}", tup)));
-
-        Ok(())
-    }
-}
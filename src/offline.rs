@@ -0,0 +1,349 @@
+use csv;
+use fnv::FnvHashMap;
+use flate2::read::GzDecoder;
+use semver::Version;
+use tar::Archive;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use errors::Error;
+use http_client::HttpClient;
+use registry::RevDepRef;
+
+const DUMP_URL: &'static str = "https://static.crates.io/db-dump.tar.gz";
+
+/// An in-memory index of the crates.io registry, built from its public
+/// database dump. Used in place of `get_rev_deps`/`resolve_rev_dep_version`
+/// when a dump is configured, so those queries need no further network
+/// access, which matters once a crate has tens of thousands of dependents.
+pub struct RevDepMap {
+    rev_deps: FnvHashMap<String, Vec<RevDepRef>>,
+    versions: FnvHashMap<String, Vec<(Version, bool)>>
+}
+
+impl RevDepMap {
+    pub fn rev_deps_of(&self, crate_name: &str) -> Vec<RevDepRef> {
+        self.rev_deps.get(crate_name).cloned().unwrap_or_else(Vec::new)
+    }
+
+    pub fn latest_version(&self, crate_name: &str) -> Option<Version> {
+        self.versions.get(crate_name).and_then(|versions| {
+            versions.iter()
+                .filter(|&&(_, yanked)| !yanked)
+                .map(|&(ref v, _)| v.clone())
+                .max()
+        })
+    }
+}
+
+/// Loads `dump_path` if it already exists on disk, otherwise downloads it
+/// from crates.io first. `dump_path` is treated as an opaque on-disk cache;
+/// callers that want a fresh copy should remove it themselves.
+pub fn load_or_fetch(client: &HttpClient, dump_path: &Path) -> Result<RevDepMap, Error> {
+    if !dump_path.exists() {
+        try!(fetch_dump(client, dump_path));
+    }
+
+    build_rev_dep_map(dump_path)
+}
+
+fn fetch_dump(client: &HttpClient, dest: &Path) -> Result<(), Error> {
+    info!("downloading crates.io db dump from {} to {:?}", DUMP_URL, dest);
+
+    let body = try!(client.get(DUMP_URL));
+
+    if let Some(parent) = dest.parent() {
+        try!(fs::create_dir_all(parent));
+    }
+
+    let mut file = try!(File::create(dest));
+    try!(file.write_all(&body));
+
+    Ok(())
+}
+
+#[derive(Clone)]
+struct RawCrate {
+    id: u64,
+    name: String
+}
+
+#[derive(Clone)]
+struct RawVersion {
+    id: u64,
+    crate_id: u64,
+    num: String,
+    yanked: bool
+}
+
+#[derive(Clone)]
+struct RawDependency {
+    version_id: u64,
+    crate_id: u64,
+    req: String
+}
+
+fn build_rev_dep_map(dump_path: &Path) -> Result<RevDepMap, Error> {
+    let mut raw_crates: Vec<RawCrate> = Vec::new();
+    let mut raw_versions: Vec<RawVersion> = Vec::new();
+    let mut raw_deps: Vec<RawDependency> = Vec::new();
+
+    {
+        let file = try!(File::open(dump_path));
+        let decoder = try!(GzDecoder::new(file));
+        let mut archive = Archive::new(decoder);
+
+        for entry in try!(archive.entries()) {
+            let mut entry = try!(entry);
+            let file_name = {
+                let path = try!(entry.path());
+                path.file_name().and_then(|n| n.to_str()).map(String::from)
+            };
+
+            match file_name.as_ref().map(|s| s.as_str()) {
+                Some("crates.csv") => raw_crates = try!(read_crates_csv(&mut entry)),
+                Some("versions.csv") => raw_versions = try!(read_versions_csv(&mut entry)),
+                Some("dependencies.csv") => raw_deps = try!(read_dependencies_csv(&mut entry)),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(assemble_rev_dep_map(raw_crates, raw_versions, raw_deps))
+}
+
+/// Builds the in-memory index from the dump's three tables. Kept separate
+/// from the archive/gzip/csv plumbing in `build_rev_dep_map` so the
+/// dedup/"current version" logic below can be exercised with plain fixture
+/// data in tests.
+fn assemble_rev_dep_map(raw_crates: Vec<RawCrate>,
+                        raw_versions: Vec<RawVersion>,
+                        raw_deps: Vec<RawDependency>) -> RevDepMap {
+    let crate_names: FnvHashMap<u64, String> = raw_crates.into_iter()
+        .map(|c| (c.id, c.name))
+        .collect();
+
+    // dependencies.csv only carries the dependent's version_id, not its
+    // crate_id directly, so we need this side table to get from one to the
+    // other before we can name the dependent crate.
+    let version_crate: FnvHashMap<u64, u64> = raw_versions.iter()
+        .map(|v| (v.id, v.crate_id))
+        .collect();
+
+    let mut versions: FnvHashMap<String, Vec<(Version, bool)>> = FnvHashMap::default();
+    for raw in &raw_versions {
+        let name = match crate_names.get(&raw.crate_id) {
+            Some(name) => name.clone(),
+            None => continue
+        };
+        if let Ok(vers) = Version::parse(&raw.num) {
+            versions.entry(name).or_insert_with(Vec::new).push((vers, raw.yanked));
+        }
+    }
+
+    // dependencies.csv has one row per historical published version of a
+    // dependent, so a crate with N published versions that all depended on
+    // us would otherwise produce N duplicate edges - and the `req` on an
+    // old version has nothing to do with the newest version we actually
+    // resolve and compile. Only keep the edge from each dependent's current
+    // (highest non-yanked) version.
+    let mut current_version_id: FnvHashMap<u64, (Version, u64)> = FnvHashMap::default();
+    for v in &raw_versions {
+        if v.yanked {
+            continue;
+        }
+        let vers = match Version::parse(&v.num) {
+            Ok(vers) => vers,
+            Err(_) => continue
+        };
+        let is_newer = match current_version_id.get(&v.crate_id) {
+            Some(&(ref cur, _)) => vers > *cur,
+            None => true
+        };
+        if is_newer {
+            current_version_id.insert(v.crate_id, (vers, v.id));
+        }
+    }
+
+    let mut rev_deps: FnvHashMap<String, Vec<RevDepRef>> = FnvHashMap::default();
+    let mut seen_edges: HashSet<(u64, u64)> = HashSet::new();
+    for raw in raw_deps {
+        let dependent_crate_id = match version_crate.get(&raw.version_id) {
+            Some(id) => *id,
+            None => continue
+        };
+        let is_current = match current_version_id.get(&dependent_crate_id) {
+            Some(&(_, ref current_id)) => *current_id == raw.version_id,
+            None => false
+        };
+        if !is_current || !seen_edges.insert((raw.crate_id, dependent_crate_id)) {
+            continue;
+        }
+
+        let dependency_name = match crate_names.get(&raw.crate_id) {
+            Some(name) => name.clone(),
+            None => continue
+        };
+        let dependent_name = match crate_names.get(&dependent_crate_id) {
+            Some(name) => name.clone(),
+            None => continue
+        };
+
+        rev_deps.entry(dependency_name).or_insert_with(Vec::new).push(RevDepRef {
+            name: dependent_name,
+            req: raw.req
+        });
+    }
+
+    info!("built offline rev dep map: {} crates, {} rev dep edges",
+          crate_names.len(), rev_deps.values().map(|v| v.len()).sum::<usize>());
+
+    RevDepMap { rev_deps: rev_deps, versions: versions }
+}
+
+/// Looks a column up by name in a CSV header row. The crates.io dump's
+/// tables have many more columns than we care about (`crates.csv` alone has
+/// 15+), and `csv`'s positional `Decodable` only lines up if our struct's
+/// field order happens to match the dump's column order exactly - so we
+/// read the header once per table and index into each row by name instead.
+fn header_index(headers: &[String], name: &str) -> Result<usize, Error> {
+    headers.iter().position(|h| h == name)
+        .ok_or_else(|| Error::CsvColumn(format!("missing column {:?}", name)))
+}
+
+fn field<'a>(record: &'a [String], idx: usize, name: &str) -> Result<&'a str, Error> {
+    record.get(idx).map(|s| s.as_str())
+        .ok_or_else(|| Error::CsvColumn(format!("row is missing column {:?}", name)))
+}
+
+fn parse_field<T: FromStr>(record: &[String], idx: usize, name: &str) -> Result<T, Error> {
+    let raw = try!(field(record, idx, name));
+    raw.parse().map_err(|_| Error::CsvColumn(format!("could not parse column {:?} from {:?}", name, raw)))
+}
+
+/// The dump is a Postgres `COPY ... CSV` export, which serializes booleans
+/// as `t`/`f` rather than `true`/`false`.
+fn parse_pg_bool(record: &[String], idx: usize, name: &str) -> Result<bool, Error> {
+    match try!(field(record, idx, name)) {
+        "t" => Ok(true),
+        "f" => Ok(false),
+        other => Err(Error::CsvColumn(format!("expected 't'/'f' for column {:?}, got {:?}", name, other)))
+    }
+}
+
+fn read_crates_csv<R: Read>(r: R) -> Result<Vec<RawCrate>, Error> {
+    let mut rdr = csv::Reader::from_reader(r);
+    let headers = try!(rdr.headers());
+    let id_idx = try!(header_index(&headers, "id"));
+    let name_idx = try!(header_index(&headers, "name"));
+
+    let mut out = Vec::new();
+    for record in rdr.records() {
+        let record = try!(record);
+        out.push(RawCrate {
+            id: try!(parse_field(&record, id_idx, "id")),
+            name: try!(field(&record, name_idx, "name")).to_string()
+        });
+    }
+    Ok(out)
+}
+
+fn read_versions_csv<R: Read>(r: R) -> Result<Vec<RawVersion>, Error> {
+    let mut rdr = csv::Reader::from_reader(r);
+    let headers = try!(rdr.headers());
+    let id_idx = try!(header_index(&headers, "id"));
+    let crate_id_idx = try!(header_index(&headers, "crate_id"));
+    let num_idx = try!(header_index(&headers, "num"));
+    let yanked_idx = try!(header_index(&headers, "yanked"));
+
+    let mut out = Vec::new();
+    for record in rdr.records() {
+        let record = try!(record);
+        out.push(RawVersion {
+            id: try!(parse_field(&record, id_idx, "id")),
+            crate_id: try!(parse_field(&record, crate_id_idx, "crate_id")),
+            num: try!(field(&record, num_idx, "num")).to_string(),
+            yanked: try!(parse_pg_bool(&record, yanked_idx, "yanked"))
+        });
+    }
+    Ok(out)
+}
+
+fn read_dependencies_csv<R: Read>(r: R) -> Result<Vec<RawDependency>, Error> {
+    let mut rdr = csv::Reader::from_reader(r);
+    let headers = try!(rdr.headers());
+    let version_id_idx = try!(header_index(&headers, "version_id"));
+    let crate_id_idx = try!(header_index(&headers, "crate_id"));
+    let req_idx = try!(header_index(&headers, "req"));
+
+    let mut out = Vec::new();
+    for record in rdr.records() {
+        let record = try!(record);
+        out.push(RawDependency {
+            version_id: try!(parse_field(&record, version_id_idx, "version_id")),
+            crate_id: try!(parse_field(&record, crate_id_idx, "crate_id")),
+            req: try!(field(&record, req_idx, "req")).to_string()
+        });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Column order deliberately does not match field declaration order,
+    // and carries extra columns the real dump has and we don't use - this
+    // is the shape that silently corrupted positional decoding before.
+    const CRATES_CSV: &'static str =
+        "created_at,id,description,name\n2015-01-01,1,a crate,alpha\n2015-01-02,2,another crate,beta\n";
+
+    const VERSIONS_CSV: &'static str =
+        "crate_id,created_at,id,yanked,num\n1,2015-01-01,10,f,1.0.0\n2,2015-01-01,20,f,1.0.0\n2,2015-06-01,21,f,2.0.0\n";
+
+    #[test]
+    fn reads_crates_csv_by_header_name_not_position() {
+        let crates = read_crates_csv(Cursor::new(CRATES_CSV.as_bytes())).unwrap();
+        assert_eq!(crates.iter().find(|c| c.id == 1).unwrap().name, "alpha");
+        assert_eq!(crates.iter().find(|c| c.id == 2).unwrap().name, "beta");
+    }
+
+    #[test]
+    fn reads_versions_csv_yanked_as_postgres_bool() {
+        let versions = read_versions_csv(Cursor::new(VERSIONS_CSV.as_bytes())).unwrap();
+        assert!(versions.iter().all(|v| !v.yanked));
+        assert_eq!(versions.iter().find(|v| v.id == 21).unwrap().num, "2.0.0");
+    }
+
+    #[test]
+    fn rejects_non_postgres_bool_literal() {
+        let bad = "id,crate_id,num,yanked\n1,1,1.0.0,true\n";
+        assert!(read_versions_csv(Cursor::new(bad.as_bytes())).is_err());
+    }
+
+    #[test]
+    fn dedupes_rev_deps_and_uses_current_version_requirement() {
+        let raw_crates = read_crates_csv(Cursor::new(CRATES_CSV.as_bytes())).unwrap();
+        let raw_versions = read_versions_csv(Cursor::new(VERSIONS_CSV.as_bytes())).unwrap();
+
+        // crate "beta" (crate_id 2) published two historical versions that
+        // both depended on "alpha" (crate_id 1), pinned to different reqs.
+        // Only the edge from its current version (version_id 21) should
+        // survive.
+        let raw_deps = vec![
+            RawDependency { version_id: 20, crate_id: 1, req: "^0.1".to_string() },
+            RawDependency { version_id: 21, crate_id: 1, req: "^1.0".to_string() },
+        ];
+
+        let map = assemble_rev_dep_map(raw_crates, raw_versions, raw_deps);
+        let rev_deps = map.rev_deps_of("alpha");
+
+        assert_eq!(rev_deps.len(), 1);
+        assert_eq!(rev_deps[0].name, "beta");
+        assert_eq!(rev_deps[0].req, "^1.0");
+    }
+}
@@ -0,0 +1,218 @@
+use rustc_serialize::json;
+use semver::{Version, VersionReq};
+
+use errors::Error;
+use http_client::HttpClient;
+
+pub type RevDepName = String;
+
+#[derive(Debug, Clone)]
+pub struct RevDep {
+    pub name: RevDepName,
+    pub vers: Version
+}
+
+/// A reverse dependency as reported by the `reverse_dependencies` endpoint,
+/// before we've resolved which published version of it we'll actually test.
+/// Carries the dependency's `req` on our crate so callers can filter out
+/// rev deps that can't possibly resolve against the version under test.
+#[derive(Debug, Clone)]
+pub struct RevDepRef {
+    pub name: RevDepName,
+    pub req: String
+}
+
+const PER_PAGE: u32 = 100;
+
+pub fn crate_url(krate: &str, call: Option<&str>) -> String {
+    let url = format!("https://crates.io/api/v1/crates/{}", krate);
+    match call {
+        Some(c) => format!("{}/{}", url, c),
+        None => url
+    }
+}
+
+pub fn get_rev_deps(client: &HttpClient, crate_name: &str) -> Result<Vec<RevDepRef>, Error> {
+    info!("Getting reverse deps for {}", crate_name);
+    let mut rev_deps = Vec::new();
+    let mut page = 1;
+
+    loop {
+        let ref url = format!("{}?page={}&per_page={}",
+                               crate_url(crate_name, Some("reverse_dependencies")),
+                               page, PER_PAGE);
+        let ref body = try!(client.get_string(url));
+        let (page_deps, total) = try!(parse_rev_deps(body));
+
+        let got_page = page_deps.len();
+        rev_deps.extend(page_deps);
+
+        if got_page == 0 || rev_deps.len() >= total {
+            break;
+        }
+        page += 1;
+    }
+
+    info!("found {} reverse deps for {}", rev_deps.len(), crate_name);
+    Ok(rev_deps)
+}
+
+fn parse_rev_deps(s: &str) -> Result<(Vec<RevDepRef>, usize), Error> {
+    #[derive(RustcEncodable, RustcDecodable)]
+    struct Response {
+        dependencies: Vec<Dep>,
+        meta: Meta
+    }
+
+    #[derive(RustcEncodable, RustcDecodable)]
+    struct Dep {
+        crate_id: String,
+        req: String
+    }
+
+    #[derive(RustcEncodable, RustcDecodable)]
+    struct Meta {
+        total: usize
+    }
+
+    let decoded: Response = try!(json::decode(&s));
+
+    fn depconv(d: Dep) -> RevDepRef {
+        RevDepRef { name: d.crate_id, req: d.req }
+    }
+
+    let revdeps = decoded.dependencies.into_iter()
+        .map(depconv).collect::<Vec<_>>();
+
+    info!("revdeps: {:?}", revdeps);
+
+    Ok((revdeps, decoded.meta.total))
+}
+
+pub fn resolve_rev_dep_version(client: &HttpClient, name: RevDepName) -> Result<RevDep, Error> {
+    info!("resolving current version for {}", name);
+    let ref url = crate_url(&name, None);
+    let ref body = try!(client.get_string(url));
+    let krate = try!(parse_crate(body));
+    let vers = try!(latest_version(&krate));
+
+    Ok(RevDep { name: name, vers: vers })
+}
+
+/// Fetches the highest non-yanked published version of `crate_name`. Used
+/// to find the version of our own crate that `base` represents, so rev
+/// deps pinned to an incompatible version can be filtered out up front.
+pub fn get_published_version(client: &HttpClient, crate_name: &str) -> Result<Version, Error> {
+    info!("resolving published version of {}", crate_name);
+    let ref url = crate_url(crate_name, None);
+    let ref body = try!(client.get_string(url));
+    let krate = try!(parse_crate(body));
+
+    latest_version(&krate)
+}
+
+fn latest_version(krate: &RegistryCrate) -> Result<Version, Error> {
+    let mut versions = Vec::new();
+    for v in &krate.versions {
+        if v.yanked {
+            continue;
+        }
+        versions.push(try!(Version::parse(&v.num)));
+    }
+
+    versions.into_iter().max().ok_or(Error::NoVersions)
+}
+
+/// Drops any rev dep whose requirement on our crate can't be satisfied by
+/// `base_version` - there's no point spending a thread-pool slot compiling
+/// a crate pinned to a version of us that isn't the one under test.
+pub fn filter_rev_deps(rev_deps: Vec<RevDepRef>, base_version: &Version) -> Vec<RevDepRef> {
+    rev_deps.into_iter().filter(|rev_dep| {
+        match VersionReq::parse(&rev_dep.req) {
+            Ok(req) => {
+                let matches = req.matches(base_version);
+                if !matches {
+                    info!("skipping {}: requirement {:?} does not match {}",
+                          rev_dep.name, rev_dep.req, base_version);
+                }
+                matches
+            }
+            Err(e) => {
+                info!("skipping {}: bad version requirement {:?}: {}",
+                      rev_dep.name, rev_dep.req, e);
+                false
+            }
+        }
+    }).collect()
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug)]
+struct RegistryCrate {
+    versions: Vec<RegistryVersion>
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug)]
+struct RegistryVersion {
+    num: String,
+    yanked: bool
+}
+
+fn parse_crate(s: &str) -> Result<RegistryCrate, Error> {
+    Ok(try!(json::decode(&s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+    use super::{filter_rev_deps, parse_rev_deps, RevDepRef};
+
+    fn rev_dep(name: &str, req: &str) -> RevDepRef {
+        RevDepRef { name: name.to_string(), req: req.to_string() }
+    }
+
+    #[test]
+    fn filter_rev_deps_keeps_matching_requirements() {
+        let base = Version::parse("1.2.0").unwrap();
+        let deps = vec![rev_dep("foo", "^1.0"), rev_dep("bar", "^2.0")];
+
+        let kept = filter_rev_deps(deps, &base);
+
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].name, "foo");
+    }
+
+    #[test]
+    fn filter_rev_deps_drops_unparseable_requirements() {
+        let base = Version::parse("1.2.0").unwrap();
+        let deps = vec![rev_dep("foo", "not a version req")];
+
+        assert!(filter_rev_deps(deps, &base).is_empty());
+    }
+
+    #[test]
+    fn parse_rev_deps_reads_dependencies_and_total() {
+        let body = r#"{
+            "dependencies": [
+                {"crate_id": "foo", "req": "^1.0"},
+                {"crate_id": "bar", "req": "^2.0"}
+            ],
+            "meta": {"total": 5}
+        }"#;
+
+        let (deps, total) = parse_rev_deps(body).unwrap();
+
+        assert_eq!(total, 5);
+        assert_eq!(deps.len(), 2);
+        assert_eq!(deps[0].name, "foo");
+        assert_eq!(deps[0].req, "^1.0");
+        assert_eq!(deps[1].name, "bar");
+    }
+
+    #[test]
+    fn parse_rev_deps_handles_an_empty_page() {
+        let body = r#"{"dependencies": [], "meta": {"total": 0}}"#;
+        let (deps, total) = parse_rev_deps(body).unwrap();
+        assert_eq!(total, 0);
+        assert!(deps.is_empty());
+    }
+}
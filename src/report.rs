@@ -0,0 +1,237 @@
+use rustc_serialize::json;
+use std::cmp;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::Write;
+
+use errors::{Error, ErrorKind};
+use {TestResult, TestResultData};
+
+/// Prints a human-readable summary to stdout, then additionally writes a
+/// JSON document to `CRUSADER_JSON_OUT` and/or a JUnit XML file to
+/// `CRUSADER_JUNIT_OUT` when those env vars are set, so CI can ingest
+/// exactly which rev deps newly broke under `next`.
+pub fn report_results(res: Result<Vec<TestResult>, Error>) {
+    let results = match res {
+        Ok(results) => results,
+        Err(Error::TestFailure(results)) => results,
+        Err(e) => {
+            println!("crusader failed: {}", e);
+            return;
+        }
+    };
+
+    print_summary(&results);
+
+    if let Ok(path) = env::var("CRUSADER_JSON_OUT") {
+        if let Err(e) = write_json(&results, &path) {
+            warn!("failed to write JSON report to {}: {}", path, e);
+        }
+    }
+
+    if let Ok(path) = env::var("CRUSADER_JUNIT_OUT") {
+        if let Err(e) = write_junit(&results, &path) {
+            warn!("failed to write JUnit report to {}: {}", path, e);
+        }
+    }
+}
+
+fn print_summary(results: &[TestResult]) {
+    let mut pass = 0;
+    let mut fail = 0;
+    let mut broken = 0;
+    let mut error = 0;
+
+    for r in results {
+        match r.data {
+            TestResultData::Pass(..) => pass += 1,
+            TestResultData::Fail(..) => fail += 1,
+            TestResultData::Broken(..) => broken += 1,
+            TestResultData::Error(..) => error += 1
+        }
+    }
+
+    println!("crusader results: {} pass, {} fail, {} broken, {} error",
+              pass, fail, broken, error);
+
+    for r in results {
+        if let TestResultData::Fail(ref base, ref next) = r.data {
+            println!("");
+            println!("REGRESSION: {} {}", r.rev_dep.name, r.rev_dep.vers);
+            println!("-- stderr diff, base vs next (- base only / + next only) --");
+            println!("{}", diff_lines(&base.stderr, &next.stderr));
+        }
+    }
+
+    if error > 0 {
+        let mut by_kind: HashMap<ErrorKind, usize> = HashMap::new();
+        for r in results {
+            if let TestResultData::Error(ref e) = r.data {
+                *by_kind.entry(e.kind()).or_insert(0) += 1;
+            }
+        }
+        let mut kinds = by_kind.into_iter().collect::<Vec<_>>();
+        kinds.sort_by_key(|&(k, _)| format!("{:?}", k));
+        let summary = kinds.iter()
+            .map(|&(k, n)| format!("{:?}: {}", k, n))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("errors by kind: {}", summary);
+    }
+}
+
+/// A minimal line-based diff (longest common subsequence) between `base`
+/// and `next`, so a regression's output can be scanned for what actually
+/// changed instead of eyeballing two full compiler dumps side by side.
+fn diff_lines(base: &str, next: &str) -> String {
+    let a = base.lines().collect::<Vec<_>>();
+    let b = next.lines().collect::<Vec<_>>();
+
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lengths[i + 1][j], lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            out.push_str(&format!("-{}\n", a[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+{}\n", b[j]));
+            j += 1;
+        }
+    }
+    while i < a.len() {
+        out.push_str(&format!("-{}\n", a[i]));
+        i += 1;
+    }
+    while j < b.len() {
+        out.push_str(&format!("+{}\n", b[j]));
+        j += 1;
+    }
+    out
+}
+
+#[derive(RustcEncodable)]
+struct JsonTestResult {
+    name: String,
+    version: String,
+    status: &'static str,
+    base_stderr: Option<String>,
+    next_stderr: Option<String>,
+    error_kind: Option<String>
+}
+
+fn to_json_results(results: &[TestResult]) -> Vec<JsonTestResult> {
+    results.iter().map(|r| {
+        let (status, base_stderr, next_stderr, error_kind) = match r.data {
+            TestResultData::Pass(..) => ("pass", None, None, None),
+            TestResultData::Fail(ref base, ref next) => {
+                ("fail", Some(base.stderr.clone()), Some(next.stderr.clone()), None)
+            }
+            TestResultData::Broken(ref base) => {
+                ("broken", Some(base.stderr.clone()), None, None)
+            }
+            TestResultData::Error(ref e) => {
+                ("error", None, Some(format!("{}", e)), Some(format!("{:?}", e.kind())))
+            }
+        };
+
+        JsonTestResult {
+            name: r.rev_dep.name.clone(),
+            version: format!("{}", r.rev_dep.vers),
+            status: status,
+            base_stderr: base_stderr,
+            next_stderr: next_stderr,
+            error_kind: error_kind
+        }
+    }).collect()
+}
+
+fn write_json(results: &[TestResult], path: &str) -> Result<(), Error> {
+    let json_results = to_json_results(results);
+    let encoded = try!(json::encode(&json_results));
+
+    let mut file = try!(File::create(path));
+    try!(file.write_all(encoded.as_bytes()));
+
+    Ok(())
+}
+
+fn write_junit(results: &[TestResult], path: &str) -> Result<(), Error> {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!("<testsuite name=\"cargo-crusader\" tests=\"{}\">\n", results.len()));
+
+    for r in results {
+        let name = format!("{} {}", r.rev_dep.name, r.rev_dep.vers);
+        out.push_str(&format!("  <testcase name=\"{}\">\n", escape_xml(&name)));
+
+        match r.data {
+            TestResultData::Pass(..) => {}
+            TestResultData::Broken(ref base) => {
+                out.push_str("    <skipped message=\"broken against base, not a regression\"/>\n");
+                out.push_str(&format!("    <system-err>{}</system-err>\n", escape_xml(&base.stderr)));
+            }
+            TestResultData::Fail(_, ref next) => {
+                out.push_str("    <failure message=\"regressed under next\">");
+                out.push_str(&escape_xml(&next.stderr));
+                out.push_str("</failure>\n");
+            }
+            TestResultData::Error(ref e) => {
+                let message = format!("{:?}: {}", e.kind(), e);
+                out.push_str(&format!("    <error message=\"{}\"/>\n", escape_xml(&message)));
+            }
+        }
+
+        out.push_str("  </testcase>\n");
+    }
+
+    out.push_str("</testsuite>\n");
+
+    let mut file = try!(File::create(path));
+    try!(file.write_all(out.as_bytes()));
+
+    Ok(())
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_lines, escape_xml};
+
+    #[test]
+    fn escape_xml_covers_attribute_unsafe_chars() {
+        let escaped = escape_xml("a & b <c> \"d\"");
+        assert_eq!(escaped, "a &amp; b &lt;c&gt; &quot;d&quot;");
+        assert!(format!("<t name=\"{}\"/>", escaped).find('"').map(|i| i == 8 || escaped.contains("&quot;")).unwrap_or(false));
+    }
+
+    #[test]
+    fn diff_lines_marks_only_the_changed_lines() {
+        let base = "one\ntwo\nthree\n";
+        let next = "one\ntwo changed\nthree\n";
+        let diff = diff_lines(base, next);
+        assert_eq!(diff, "-two\n+two changed\n");
+    }
+
+    #[test]
+    fn diff_lines_of_identical_input_is_empty() {
+        assert_eq!(diff_lines("same\n", "same\n"), "");
+    }
+}